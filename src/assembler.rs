@@ -0,0 +1,225 @@
+use super::virtual_machine::BF_CHARS;
+use super::virtual_machine_errors::VMErrKind;
+use std::collections::HashMap;
+
+/// The opcode value used to mark a run of skipped non-BrainFuck characters.
+const FIL_CHAR: u8 = 16u8;
+/// The opcode value used to mark a fill jump inserted for a non-BrainFuck run.
+const FIL_JUMP: u8 = 17u8;
+
+/// The character a disassembly uses for a fill-character marker.
+const FIL_CHAR_GLYPH: char = '_';
+/// The character a disassembly uses for a fill-jump marker.
+const FIL_JUMP_GLYPH: char = '!';
+
+/// Magic bytes at the start of a loadable program.
+const MAGIC: [u8; 4] = *b"BFVM";
+/// The bytecode format version understood by this module.
+const VERSION: u8 = 1u8;
+
+/// Turns an opcode stream back into its source characters for debugging and round-tripping.
+/// The 16 instruction opcodes map to their `BF_CHARS`; fill markers are rendered as `_` (skipped
+/// characters) and `!` (fill jump), and any unexpected opcode as `?`.
+/// # Examples
+/// ```
+/// let text = disassemble(&[2u8, 2u8, 6u8]);
+/// assert_eq!(text, "++.");
+/// ```
+pub fn disassemble(prog: &[u8]) -> String {
+    prog.iter()
+        .map(|code| match *code {
+            c if usize::from(c) < BF_CHARS.len() => BF_CHARS[usize::from(c)],
+            FIL_CHAR => FIL_CHAR_GLYPH,
+            FIL_JUMP => FIL_JUMP_GLYPH,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// The decoded contents of a loadable program: enough to rebuild a `BFVM` without re-parsing
+/// source or rebuilding the loop jump map.
+pub struct Bytecode {
+    /// The page bound the tape was created with.
+    pub max_pages: u32,
+    /// The number of workspaces the tape was created with.
+    pub num_workspaces: usize,
+    /// The memory-mapped peripheral window, as `(page, base, len)`, if one was configured.
+    pub mmio: Option<(u16, u16, u16)>,
+    /// The precomputed loop and fill jump map.
+    pub jump_map: HashMap<usize, usize>,
+    /// The instruction opcode stream.
+    pub prog: Vec<u8>,
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// A little-endian cursor over a byte slice that reports the offset on failure.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VMErrKind> {
+        if self.pos + n > self.bytes.len() {
+            return Err(VMErrKind::MalformedBytecode(self.pos));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, VMErrKind> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, VMErrKind> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, VMErrKind> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl Bytecode {
+    /// Packs the program into the loadable binary format: a magic/version header, the tape and
+    /// peripheral layout, the jump map, and the opcode stream packed four bits per instruction.
+    /// Fill markers do not fit in four bits, so their positions are recorded alongside the packed
+    /// stream and restored on decode.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        write_u32(&mut out, self.max_pages);
+        write_u32(&mut out, self.num_workspaces as u32);
+
+        match self.mmio {
+            Some((page, base, len)) => {
+                out.push(1);
+                write_u16(&mut out, page);
+                write_u16(&mut out, base);
+                write_u16(&mut out, len);
+            }
+            None => out.push(0),
+        }
+
+        let mut entries: Vec<(usize, usize)> =
+            self.jump_map.iter().map(|(k, v)| (*k, *v)).collect();
+        entries.sort_unstable();
+        write_u32(&mut out, entries.len() as u32);
+        for (key, value) in entries {
+            write_u32(&mut out, key as u32);
+            write_u32(&mut out, value as u32);
+        }
+
+        let fil_chars: Vec<usize> = positions(&self.prog, FIL_CHAR);
+        let fil_jumps: Vec<usize> = positions(&self.prog, FIL_JUMP);
+        write_positions(&mut out, &fil_chars);
+        write_positions(&mut out, &fil_jumps);
+
+        write_u32(&mut out, self.prog.len() as u32);
+        for pair in self.prog.chunks(2) {
+            let low = pair[0] & 0x0F;
+            let high = pair.get(1).map_or(0, |c| c & 0x0F);
+            out.push(low | (high << 4));
+        }
+        out
+    }
+
+    /// Parses a loadable program produced by `encode`.
+    /// # Errors
+    /// Returns `VMErrKind::MalformedBytecode` with the offending byte offset if the magic, version,
+    /// or any field is missing or inconsistent. For a fill-marker position past the end of the
+    /// program, the error carries that program position instead of a byte offset.
+    pub fn decode(bytes: &[u8]) -> Result<Bytecode, VMErrKind> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(VMErrKind::MalformedBytecode(0));
+        }
+        if reader.u8()? != VERSION {
+            return Err(VMErrKind::MalformedBytecode(4));
+        }
+
+        let max_pages = reader.u32()?;
+        let num_workspaces = reader.u32()? as usize;
+
+        let mmio = match reader.u8()? {
+            0 => None,
+            1 => Some((reader.u16()?, reader.u16()?, reader.u16()?)),
+            _ => return Err(VMErrKind::MalformedBytecode(reader.pos - 1)),
+        };
+
+        let entry_count = reader.u32()? as usize;
+        let mut jump_map = HashMap::new();
+        for _ in 0..entry_count {
+            let key = reader.u32()? as usize;
+            let value = reader.u32()? as usize;
+            jump_map.insert(key, value);
+        }
+
+        let fil_chars = read_positions(&mut reader)?;
+        let fil_jumps = read_positions(&mut reader)?;
+
+        let prog_len = reader.u32()? as usize;
+        let packed = reader.take(prog_len.div_ceil(2))?;
+        let mut prog = Vec::with_capacity(prog_len);
+        for byte in packed {
+            prog.push(byte & 0x0F);
+            if prog.len() < prog_len {
+                prog.push((byte >> 4) & 0x0F);
+            }
+        }
+        for pos in fil_chars {
+            *prog.get_mut(pos).ok_or(VMErrKind::MalformedBytecode(pos))? = FIL_CHAR;
+        }
+        for pos in fil_jumps {
+            *prog.get_mut(pos).ok_or(VMErrKind::MalformedBytecode(pos))? = FIL_JUMP;
+        }
+
+        Ok(Bytecode {
+            max_pages,
+            num_workspaces,
+            mmio,
+            jump_map,
+            prog,
+        })
+    }
+}
+
+/// Collects the indices at which `prog` holds the opcode `code`.
+fn positions(prog: &[u8], code: u8) -> Vec<usize> {
+    prog.iter()
+        .enumerate()
+        .filter(|(_, c)| **c == code)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+fn write_positions(out: &mut Vec<u8>, positions: &[usize]) {
+    write_u32(out, positions.len() as u32);
+    for pos in positions {
+        write_u32(out, *pos as u32);
+    }
+}
+
+fn read_positions(reader: &mut Reader) -> Result<Vec<usize>, VMErrKind> {
+    let count = reader.u32()? as usize;
+    let mut positions = Vec::with_capacity(count);
+    for _ in 0..count {
+        positions.push(reader.u32()? as usize);
+    }
+    Ok(positions)
+}
@@ -1,40 +1,59 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use super::snapshot::DataTapeSnapshot;
 use super::virtual_machine_errors::VMErrKind;
 
-/// A paginated data tape.
+/// The number of cells in a single allocation block.
+const BLOCK_SIZE: usize = 256;
+
+/// A contiguous run of cells, allocated lazily on first write.
+type Block = Box<[u16; BLOCK_SIZE]>;
+
+/// A paginated, sparsely allocated data tape.
+/// Each 65,536-cell page is divided into fixed-size blocks; a block is only allocated the first
+/// time one of its cells is written, and missing blocks read as all-zero. Workspaces are stored
+/// the same way, keyed by workspace index, so an untouched page or workspace costs nothing.
 pub struct DataTape {
-    tapes: HashMap<u16, [u16; 0x10000]>,
+    tapes: BTreeMap<(u16, u16), Block>,
     pointer: u16,
     page: u16,
     max_pages: u32,
 
     num_workspaces: usize,
-    workspaces: Vec<[u16; 0x10000]>,
+    workspaces: BTreeMap<(usize, u16), Block>,
     workspace_pointer: usize,
 }
 
+/// Splits a cell pointer into its block index and the offset within that block.
+fn block_offset(pointer: u16) -> (u16, usize) {
+    (pointer / BLOCK_SIZE as u16, usize::from(pointer) % BLOCK_SIZE)
+}
+
+/// Rebuilds a block from snapshot data, padding or truncating to the block size.
+fn restore_block(data: &[u16]) -> Block {
+    let mut block = Box::new([0u16; BLOCK_SIZE]);
+    for (cell, value) in block.iter_mut().zip(data.iter()) {
+        *cell = *value;
+    }
+    block
+}
+
 impl DataTape {
 
     pub fn new_with_workspaces(max_pages: u32, num_workspaces: usize) -> DataTape {
-        let mut workspaces: Vec<[u16; 0x10000]> = Vec::new();
-        for _ in 0..num_workspaces {
-            workspaces.push([0u16; 0x10000]);
-        }
         DataTape{
-            tapes: HashMap::new(),
+            tapes: BTreeMap::new(),
             pointer: 0,
             page: 0,
             max_pages,
 
             num_workspaces,
-            workspaces,
+            workspaces: BTreeMap::new(),
             workspace_pointer: 0,
         }
     }
 
-    //TODO: bounds on max pages
     /// Constructs a new, empty `DataTape`.
-    /// The tape will create pages when read from or written to the first time.
+    /// The tape will allocate blocks when written to the first time.
     /// # Examples
     /// ```
     /// let mut tape: DataTape = DataTape::new(0x10000);
@@ -65,6 +84,7 @@ impl DataTape {
     }
 
     /// Set the current memory page.
+    /// Pages outside `max_pages` are ignored, leaving the current page unchanged.
     /// # Examples
     /// ```
     /// let mut tape = DataTape::new(0x10000);
@@ -72,7 +92,9 @@ impl DataTape {
     ///
     /// ```
     pub fn set_page(&mut self, address: u16) {
-        self.page = address;
+        if u32::from(address) < self.max_pages {
+            self.page = address;
+        }
     }
 
     /// Get the current memory page.
@@ -88,6 +110,7 @@ impl DataTape {
     }
 
     /// Set the value at the pointer on the tape.
+    /// Allocates the backing block on first write.
     /// # Errors
     /// If the page is out of bounds, then a `VMErrKind::InvalidPage` error is returned.
     /// # Examples
@@ -99,24 +122,22 @@ impl DataTape {
         if u32::from(self.page) >= self.max_pages {
             return Err(VMErrKind::InvalidPage(self.page, self.max_pages))
         }
-        
-        if self.workspace_pointer > 0 {
-            self.workspaces[self.workspace_pointer - 1][usize::from(self.pointer)] = value; 
-            return Ok(());
-        }
 
-        let res = self.tapes.get_mut(&self.page);
-        if let Some(data) = res {
-            data[usize::from(self.pointer)] = value;
+        let (block, offset) = block_offset(self.pointer);
+        if self.workspace_pointer > 0 {
+            self.workspaces
+                .entry((self.workspace_pointer - 1, block))
+                .or_insert_with(|| Box::new([0u16; BLOCK_SIZE]))[offset] = value;
         } else {
-            let mut tape = [0u16; 0x10000];
-            tape[usize::from(self.pointer)] = value;
-            self.tapes.insert(self.page, tape);
+            self.tapes
+                .entry((self.page, block))
+                .or_insert_with(|| Box::new([0u16; BLOCK_SIZE]))[offset] = value;
         }
         Ok(())
     }
 
     /// Get the value at the pointer on the tape.
+    /// Blocks that have never been written read as zero.
     /// # Errors
     /// If the page is out of bounds, then a `VMErrKind::InvalidPage` error is returned.
     /// # Examples
@@ -130,17 +151,17 @@ impl DataTape {
             return Err(VMErrKind::InvalidPage(self.page, self.max_pages))
         }
 
-        if self.workspace_pointer > 0 {
-            return Ok(self.workspaces[self.workspace_pointer - 1][usize::from(self.pointer)]);
-        }
-
-        let res = self.tapes.get(&self.page);
-        if let Some(data) = res {
-            Ok(data[usize::from(self.pointer)])
+        let (block, offset) = block_offset(self.pointer);
+        let value = if self.workspace_pointer > 0 {
+            self.workspaces
+                .get(&(self.workspace_pointer - 1, block))
+                .map_or(0, |data| data[offset])
         } else {
-            self.tapes.insert(self.page, [0; 0x10000]);
-            Ok(0)
-        }
+            self.tapes
+                .get(&(self.page, block))
+                .map_or(0, |data| data[offset])
+        };
+        Ok(value)
     }
 
     /// Gets the maximum number of accessible pages.
@@ -153,10 +174,56 @@ impl DataTape {
         self.max_pages
     }
 
+    /// Gets the number of workspaces attached to the tape.
+    pub fn get_num_workspaces(&self) -> usize {
+        self.num_workspaces
+    }
+
+    /// Captures the tape's position and all allocated blocks into a serializable snapshot.
+    pub fn snapshot(&self) -> DataTapeSnapshot {
+        let tapes = self
+            .tapes
+            .iter()
+            .map(|((page, block), data)| (*page, *block, data.to_vec()))
+            .collect();
+        let workspaces = self
+            .workspaces
+            .iter()
+            .map(|((workspace, block), data)| (*workspace, *block, data.to_vec()))
+            .collect();
+        DataTapeSnapshot {
+            pointer: self.pointer,
+            page: self.page,
+            max_pages: self.max_pages,
+            num_workspaces: self.num_workspaces,
+            workspace_pointer: self.workspace_pointer,
+            tapes,
+            workspaces,
+        }
+    }
+
+    /// Rebuilds a `DataTape` from a snapshot produced by `snapshot`.
+    pub fn from_snapshot(snapshot: &DataTapeSnapshot) -> DataTape {
+        let mut tape = DataTape::new_with_workspaces(snapshot.max_pages, snapshot.num_workspaces);
+        tape.pointer = snapshot.pointer;
+        tape.page = snapshot.page;
+        tape.workspace_pointer = snapshot.workspace_pointer;
+        for (page, block, data) in &snapshot.tapes {
+            tape.tapes.insert((*page, *block), restore_block(data));
+        }
+        for (workspace, block, data) in &snapshot.workspaces {
+            tape.workspaces
+                .insert((*workspace, *block), restore_block(data));
+        }
+        tape
+    }
+
     pub fn next_workspace(&mut self) {
         if self.workspace_pointer == self.num_workspaces {
-            self.page += 1;
-            self.workspace_pointer = 0;
+            if u32::from(self.page) + 1 < self.max_pages {
+                self.page += 1;
+                self.workspace_pointer = 0;
+            }
         } else {
             self.workspace_pointer += 1;
         }
@@ -164,8 +231,10 @@ impl DataTape {
 
     pub fn prev_workspace(&mut self) {
         if self.workspace_pointer == 0 {
-            self.page -= 1;
-            self.workspace_pointer = self.num_workspaces;
+            if self.page > 0 {
+                self.page -= 1;
+                self.workspace_pointer = self.num_workspaces;
+            }
         } else {
             self.workspace_pointer -= 1;
         }
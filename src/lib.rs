@@ -6,4 +6,10 @@ pub mod data_tape;
 pub mod peripheral_tape;
 /// Provides the errors used in this crate.
 pub mod virtual_machine_errors;
+/// Provides the recoverable trap/handler subsystem.
+pub mod trap;
+/// Provides the disassembler and the loadable bytecode format.
+pub mod assembler;
+/// Provides the serializable VM and data tape snapshot structures.
+pub mod snapshot;
 
@@ -0,0 +1,32 @@
+/// A resumable snapshot of a `DataTape`.
+/// The sparse block map serializes as `(page, block, data)` tuples for the main pages and
+/// `(workspace, block, data)` tuples for the workspaces, so only allocated blocks are captured.
+pub struct DataTapeSnapshot {
+    /// The data pointer at snapshot time.
+    pub pointer: u16,
+    /// The current page at snapshot time.
+    pub page: u16,
+    /// The page bound the tape was created with.
+    pub max_pages: u32,
+    /// The number of workspaces the tape was created with.
+    pub num_workspaces: usize,
+    /// The workspace pointer at snapshot time.
+    pub workspace_pointer: usize,
+    /// The allocated main-page blocks, as `(page, block, data)`.
+    pub tapes: Vec<(u16, u16, Vec<u16>)>,
+    /// The allocated workspace blocks, as `(workspace, block, data)`.
+    pub workspaces: Vec<(usize, u16, Vec<u16>)>,
+}
+
+/// A complete, resumable snapshot of a `BFVM`.
+/// It captures the program pointer, the buffer register, and the entire data tape. Peripherals are
+/// external to the VM and are deliberately excluded: restoring a snapshot does not replay any
+/// peripheral side effects.
+pub struct VMSnapshot {
+    /// The program pointer at snapshot time.
+    pub pointer: usize,
+    /// The buffer register at snapshot time.
+    pub buffer: u16,
+    /// The data tape contents.
+    pub data_tape: DataTapeSnapshot,
+}
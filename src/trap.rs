@@ -0,0 +1,66 @@
+use super::virtual_machine_errors::VMErrKind;
+
+/// The kind of fault that caused a trap.
+/// Faults that previously aborted the whole program by `panic!` (undefined opcodes and missing
+/// jump-map entries) and the recoverable `VMErrKind` faults are all surfaced through this enum so
+/// a host can intercept them.
+pub enum TrapType {
+    /// The current page is outside `max_pages`. Carries the page and the page limit.
+    InvalidPage(u16, u32),
+    /// A peripheral read or write failed. Carries the value and the peripheral address.
+    PeripheralIO(u16, u16),
+    /// A peripheral address was accessed that has no peripheral behind it.
+    InvalidPeripheralAccess(u16),
+    /// A loop bracket had no matching jump-map entry. Carries the program pointer.
+    UnmatchedLoop(usize),
+    /// The opcode at the program pointer is not a defined instruction. Carries the opcode.
+    UndefinedOpcode(u8),
+}
+
+/// What the VM should do after a trap handler returns.
+pub enum TrapAction {
+    /// Continue execution at `pointer + 1`, as if the faulting instruction had completed.
+    Resume,
+    /// Redirect the program pointer to the given address.
+    Jump(usize),
+    /// Propagate the fault out of `run` as a `VMErrKind`, the same as the handler-less behavior.
+    Abort(VMErrKind),
+}
+
+/// The state made available to a trap handler when a fault occurs.
+/// The handler sees the faulting instruction and the relevant machine registers, and returns a
+/// `TrapAction` describing how to proceed.
+pub struct TrapContext {
+    /// The fault that triggered the trap.
+    pub trap_type: TrapType,
+    /// The program pointer of the faulting instruction.
+    pub pointer: usize,
+    /// The opcode of the faulting instruction.
+    pub opcode: u8,
+    /// The data tape pointer at the time of the fault.
+    pub data_pointer: u16,
+    /// The data tape page at the time of the fault.
+    pub data_page: u16,
+    /// The buffer register at the time of the fault.
+    pub buffer: u16,
+}
+
+impl From<VMErrKind> for TrapType {
+    fn from(err: VMErrKind) -> TrapType {
+        match err {
+            VMErrKind::InvalidPage(page, max) => TrapType::InvalidPage(page, max),
+            VMErrKind::PeripheralIOErr(value, address) => TrapType::PeripheralIO(value, address),
+            VMErrKind::InvalidPeripheralTapeAccess(address) => {
+                TrapType::InvalidPeripheralAccess(address)
+            }
+            VMErrKind::UnmachedLoopParentheses(loc) => TrapType::UnmatchedLoop(loc),
+            // The crate raises the remaining faults only while building the peripheral tape,
+            // mapping an MMIO window or decoding bytecode, not while stepping. A peripheral that
+            // returns one of them from `read` or `write` reaches this arm and panics.
+            VMErrKind::OverlappingPeripheralAddresses(..)
+            | VMErrKind::PeripheralWindowOutOfRange(..)
+            | VMErrKind::OverlappingPeripheralWindow(..)
+            | VMErrKind::MalformedBytecode(..) => unreachable!(),
+        }
+    }
+}
@@ -1,8 +1,23 @@
+use super::assembler::Bytecode;
 use super::data_tape::DataTape;
 use super::peripheral_tape::PeripheralTape;
+use super::snapshot::VMSnapshot;
+use super::trap::{TrapAction, TrapContext, TrapType};
 use super::virtual_machine_errors::VMErrKind;
 use std::collections::HashMap;
 
+/// A handler invoked when a fault traps. See `BFVM::set_trap_handler`.
+type TrapHandler<'a> = dyn FnMut(&mut TrapContext) -> TrapAction + 'a;
+
+/// A contiguous data-tape address window on a single page that is wired to the peripheral tape.
+/// Data accesses that land inside the window are redirected to the peripheral tape, offset from
+/// `base`, instead of the block store.
+struct MmioWindow {
+    page: u16,
+    base: u16,
+    len: u16,
+}
+
 pub struct BFVM<'a> {
     prog: Vec<u8>,
     jump_map: HashMap<usize, usize>,
@@ -11,6 +26,15 @@ pub struct BFVM<'a> {
 
     pointer: usize,
     buffer: u16,
+
+    trap_handler: Option<Box<TrapHandler<'a>>>,
+
+    cycle_counter: u64,
+    timer_period: u32,
+    timer_counter: u32,
+    timer_isr: usize,
+
+    mmio: Option<MmioWindow>,
 }
 
 pub const BF_CHARS: [char; 16] = [
@@ -168,16 +192,275 @@ impl<'a> BFVM<'a> {
             peripheral_tape,
             pointer: 0,
             buffer: 0,
+            trap_handler: None,
+            cycle_counter: 0,
+            timer_period: 0,
+            timer_counter: 0,
+            timer_isr: 0,
+            mmio: None,
         })
     }
 
+    /// Serializes the program into the loadable bytecode format so it can be analyzed once and
+    /// reloaded without re-parsing source or rebuilding the loop jump map. The tape and peripheral
+    /// layout, the jump map, and the packed opcodes are captured; the tape contents and peripherals
+    /// are not.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        Bytecode {
+            max_pages: self.data_tape.get_max_pages(),
+            num_workspaces: self.data_tape.get_num_workspaces(),
+            mmio: self
+                .mmio
+                .as_ref()
+                .map(|window| (window.page, window.base, window.len)),
+            jump_map: self.jump_map.clone(),
+            prog: self.prog.clone(),
+        }
+        .encode()
+    }
+
+    /// Rebuilds a `BFVM` from bytecode produced by `to_bytecode`, wiring it to the given peripheral
+    /// tape. The loaded program starts from the beginning with a fresh, empty data tape.
+    /// # Errors
+    /// Returns `VMErrKind::MalformedBytecode` if the bytes cannot be decoded, and
+    /// `VMErrKind::UnmachedLoopParentheses` if the loop brackets and the stored jump map are not
+    /// internally consistent.
+    pub fn from_bytecode(
+        bytes: &[u8],
+        peripheral_tape: &'a mut PeripheralTape<'a>,
+    ) -> Result<BFVM<'a>, VMErrKind> {
+        let code = Bytecode::decode(bytes)?;
+
+        // The stored jump map must agree with the brackets actually present in the program.
+        let expected = create_jump_map(&code.prog)?;
+        for (key, value) in &expected {
+            if code.jump_map.get(key) != Some(value) {
+                return Err(VMErrKind::UnmachedLoopParentheses(*key));
+            }
+        }
+
+        let mut vm = BFVM {
+            prog: code.prog,
+            jump_map: code.jump_map,
+            data_tape: DataTape::new_with_workspaces(code.max_pages, code.num_workspaces),
+            peripheral_tape,
+            pointer: 0,
+            buffer: 0,
+            trap_handler: None,
+            cycle_counter: 0,
+            timer_period: 0,
+            timer_counter: 0,
+            timer_isr: 0,
+            mmio: None,
+        };
+        if let Some((page, base, len)) = code.mmio {
+            vm.map_peripheral_window(page, base, len)?;
+        }
+        Ok(vm)
+    }
+
+    /// Captures a complete, resumable snapshot of the VM: the program pointer, the buffer, and the
+    /// entire data tape. Peripherals are external and are not captured, so their side effects are
+    /// not replayed on restore.
+    pub fn snapshot(&self) -> VMSnapshot {
+        VMSnapshot {
+            pointer: self.pointer,
+            buffer: self.buffer,
+            data_tape: self.data_tape.snapshot(),
+        }
+    }
+
+    /// Restores a snapshot taken by `snapshot` into this VM, which keeps its existing program and
+    /// peripheral tape. The program pointer, buffer, and data tape are replaced wholesale.
+    pub fn restore(&mut self, snapshot: &VMSnapshot) {
+        self.pointer = snapshot.pointer;
+        self.buffer = snapshot.buffer;
+        self.data_tape = DataTape::from_snapshot(&snapshot.data_tape);
+    }
+
+    /// Maps a contiguous window of `len` cells starting at `base` on `page` to the peripheral tape.
+    /// While the window is mapped, ordinary data reads and writes that land inside it are routed to
+    /// `peripheral_tape.read`/`write` at the address offset from `base`, letting a program drive
+    /// peripherals through normal memory traffic.
+    /// # Errors
+    /// Returns `VMErrKind::PeripheralWindowOutOfRange` if the window extends past the end of a page,
+    /// and `VMErrKind::OverlappingPeripheralWindow` if it overlaps a window already mapped on the
+    /// same page.
+    pub fn map_peripheral_window(
+        &mut self,
+        page: u16,
+        base: u16,
+        len: u16,
+    ) -> Result<(), VMErrKind> {
+        if u32::from(base) + u32::from(len) > 0x10000 {
+            return Err(VMErrKind::PeripheralWindowOutOfRange(base, len));
+        }
+        if let Some(window) = &self.mmio {
+            let new_end = u32::from(base) + u32::from(len);
+            let old_end = u32::from(window.base) + u32::from(window.len);
+            if window.page == page
+                && u32::from(base) < old_end
+                && u32::from(window.base) < new_end
+            {
+                return Err(VMErrKind::OverlappingPeripheralWindow(page, base, len));
+            }
+        }
+        self.mmio = Some(MmioWindow { page, base, len });
+        Ok(())
+    }
+
+    /// Returns the peripheral address for the current data pointer when it lies inside the mapped
+    /// MMIO window, or `None` when the access should hit ordinary memory.
+    fn mmio_address(&self) -> Option<u16> {
+        let window = self.mmio.as_ref()?;
+        if self.data_tape.get_page() != window.page {
+            return None;
+        }
+        let pointer = self.data_tape.get_pointer();
+        let end = u32::from(window.base) + u32::from(window.len);
+        if u32::from(pointer) >= u32::from(window.base) && u32::from(pointer) < end {
+            Some(pointer - window.base)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the cell at the current data pointer, redirecting to the peripheral tape when the
+    /// pointer lies inside the mapped MMIO window.
+    fn read_cell(&mut self) -> Result<u16, VMErrKind> {
+        if let Some(address) = self.mmio_address() {
+            self.peripheral_tape.read(address)
+        } else {
+            self.data_tape.get_value()
+        }
+    }
+
+    /// Writes `value` to the cell at the current data pointer, redirecting to the peripheral tape
+    /// when the pointer lies inside the mapped MMIO window.
+    fn write_cell(&mut self, value: u16) -> Result<(), VMErrKind> {
+        if let Some(address) = self.mmio_address() {
+            self.peripheral_tape.write(value, address)
+        } else {
+            self.data_tape.set_value(value)
+        }
+    }
+
+    /// Registers a handler that is invoked whenever an instruction traps.
+    /// The handler receives a `TrapContext` describing the fault and the machine registers, and
+    /// returns a `TrapAction` telling the VM to resume, jump, or abort. With no handler registered
+    /// the VM behaves exactly as before: recoverable faults return their `VMErrKind` and undefined
+    /// opcodes or missing jump-map entries abort.
+    pub fn set_trap_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut TrapContext) -> TrapAction + 'a,
+    {
+        self.trap_handler = Some(Box::new(handler));
+    }
+
+    /// Removes any registered trap handler, restoring the fail-fast behavior.
+    pub fn clear_trap_handler(&mut self) {
+        self.trap_handler = None;
+    }
+
+    /// Dispatches a trap to the registered handler, applying the returned `TrapAction`.
+    /// With no handler the fault keeps its original behavior: recoverable faults propagate as a
+    /// `VMErrKind` and undefined opcodes or missing jump-map entries panic.
+    fn dispatch_trap(&mut self, trap: TrapType) -> Result<bool, VMErrKind> {
+        if let Some(mut handler) = self.trap_handler.take() {
+            let mut context = TrapContext {
+                trap_type: trap,
+                pointer: self.pointer,
+                opcode: self.prog[self.pointer],
+                data_pointer: self.data_tape.get_pointer(),
+                data_page: self.data_tape.get_page(),
+                buffer: self.buffer,
+            };
+            let action = handler(&mut context);
+            self.trap_handler = Some(handler);
+            match action {
+                TrapAction::Resume => self.pointer += 1,
+                TrapAction::Jump(address) => self.pointer = address,
+                TrapAction::Abort(err) => return Err(err),
+            }
+            Ok(true)
+        } else {
+            match trap {
+                TrapType::InvalidPage(page, max) => Err(VMErrKind::InvalidPage(page, max)),
+                TrapType::PeripheralIO(value, address) => {
+                    Err(VMErrKind::PeripheralIOErr(value, address))
+                }
+                TrapType::InvalidPeripheralAccess(address) => {
+                    Err(VMErrKind::InvalidPeripheralTapeAccess(address))
+                }
+                TrapType::UnmatchedLoop(loc) => {
+                    panic!(format!("Jump map entry not defined at {}", loc))
+                }
+                TrapType::UndefinedOpcode(code) => {
+                    panic!(format!("Attempt to run undefined code: {}", code))
+                }
+            }
+        }
+    }
+
+    /// Arms the cycle-counted timer. Every `period` cycles the down-counter reloads and the program
+    /// pointer is redirected to `isr_addr`, so the host can schedule an interrupt service routine
+    /// for cooperative multitasking or watchdog use. A `period` of 0 disables the timer, the same
+    /// as `clear_timer`.
+    pub fn set_timer(&mut self, period: u32, isr_addr: usize) {
+        self.timer_period = period;
+        self.timer_counter = period;
+        self.timer_isr = isr_addr;
+    }
+
+    /// Disables the cycle-counted timer.
+    pub fn clear_timer(&mut self) {
+        self.timer_period = 0;
+        self.timer_counter = 0;
+    }
+
+    /// Returns the number of cycles executed so far.
+    pub fn get_cycle_counter(&self) -> u64 {
+        self.cycle_counter
+    }
+
+    /// Advances the cycle counter and the timer down-counter by one cycle. When the timer is armed
+    /// and its down-counter reaches zero it reloads with the period and redirects the program
+    /// pointer to the interrupt service routine; the redirected instruction runs on the next
+    /// `next`, so a fault inside the ISR surfaces through the trap path instead of recursing here.
+    fn tick_timer(&mut self) {
+        self.cycle_counter = self.cycle_counter.wrapping_add(1);
+        if self.timer_period == 0 {
+            return;
+        }
+        self.timer_counter -= 1;
+        if self.timer_counter == 0 {
+            self.timer_counter = self.timer_period;
+            self.pointer = self.timer_isr;
+        }
+    }
+
     /// Runs the next character.
     pub fn next(&mut self) -> Result<bool, VMErrKind> {
         if self.pointer >= self.prog.len() {
             return Ok(false);
         }
         let code = self.prog[self.pointer];
-        self.pointer = match code {
+        let running = match self.step(code) {
+            Ok(next_pointer) => {
+                self.pointer = next_pointer;
+                true
+            }
+            Err(trap) => self.dispatch_trap(trap)?,
+        };
+        self.tick_timer();
+        Ok(running)
+    }
+
+    /// Executes the instruction `code` at the current program pointer, returning the next program
+    /// pointer. Recoverable faults and the formerly-panicking cases are reported as a `TrapType`
+    /// so `next` can route them through the trap subsystem.
+    fn step(&mut self, code: u8) -> Result<usize, TrapType> {
+        let next = match code {
             BF_PTR_INC => {
                 self.data_tape
                     .set_pointer(self.data_tape.get_pointer().wrapping_add(1));
@@ -189,75 +472,75 @@ impl<'a> BFVM<'a> {
                 self.pointer + 1
             }
             BF_DATA_INC => {
-                let val = self.data_tape.get_value()?.wrapping_add(1);
-                self.data_tape.set_value(val)?;
+                let val = self.read_cell()?.wrapping_add(1);
+                self.write_cell(val)?;
                 self.pointer + 1
             }
             BF_DATA_DEC => {
-                let val = self.data_tape.get_value()?.wrapping_sub(1);
-                self.data_tape.set_value(val)?;
+                let val = self.read_cell()?.wrapping_sub(1);
+                self.write_cell(val)?;
                 self.pointer + 1
             }
             BF_LOOP_OPEN => {
-                if self.data_tape.get_value()? == 0 {
-                    *(self.jump_map.get(&self.pointer).expect(&format!(
-                        "Jump map entry not defined for open loop at {}",
-                        self.pointer
-                    )))
+                if self.read_cell()? == 0 {
+                    *self
+                        .jump_map
+                        .get(&self.pointer)
+                        .ok_or(TrapType::UnmatchedLoop(self.pointer))?
                 } else {
                     self.pointer + 1
                 }
             }
             BF_LOOP_CLOSE => {
-                if self.data_tape.get_value()? != 0 {
-                    *(self.jump_map.get(&self.pointer).expect(&format!(
-                        "Jump map entry not defined for close loop at {}",
-                        self.pointer
-                    )))
+                if self.read_cell()? != 0 {
+                    *self
+                        .jump_map
+                        .get(&self.pointer)
+                        .ok_or(TrapType::UnmatchedLoop(self.pointer))?
                 } else {
                     self.pointer + 1
                 }
             }
             BF_OUTPUT => {
-                self.peripheral_tape
-                    .write(self.data_tape.get_value()?, self.buffer)?;
+                let value = self.read_cell()?;
+                self.peripheral_tape.write(value, self.buffer)?;
                 self.pointer + 1
             }
             BF_INPUT => {
                 let ret = self.peripheral_tape.read(self.buffer)?;
-                self.data_tape.set_value(ret)?;
+                self.write_cell(ret)?;
                 self.pointer + 1
             }
             BF_PTR_JUMP => {
-                let point = self.data_tape.get_value()?;
+                let point = self.read_cell()?;
                 self.data_tape.set_pointer(point);
                 self.pointer + 1
             }
             BF_TO_BUF => {
-                self.buffer = self.data_tape.get_value()?;
+                self.buffer = self.read_cell()?;
                 self.pointer + 1
             }
             BF_FROM_BUF => {
-                self.data_tape.set_value(self.buffer)?;
+                self.write_cell(self.buffer)?;
                 self.pointer + 1
             }
             BF_ROTATE => {
-                let val = self.data_tape.get_value()?;
+                let val = self.read_cell()?;
                 let or = if val & 0x0001 == 0x0001 {
                     0x8000
                 } else {
                     0x0000
                 };
-                self.data_tape.set_value((val >> 1) | or)?;
+                self.write_cell((val >> 1) | or)?;
                 self.pointer + 1
             }
             BF_NAND => {
-                let val = self.data_tape.get_value()?;
-                self.data_tape.set_value(!(val & self.buffer))?;
+                let val = self.read_cell()?;
+                self.write_cell(!(val & self.buffer))?;
                 self.pointer + 1
             }
             BF_PAGE_JUMP => {
-                let page = self.data_tape.get_value()?;
+                let page = self.read_cell()?;
                 self.data_tape.set_page(page);
                 self.pointer + 1
             }
@@ -269,16 +552,14 @@ impl<'a> BFVM<'a> {
                 self.data_tape.prev_workspace();
                 self.pointer + 1
             }
-            FIL_JUMP => {
-                *(self.jump_map.get(&self.pointer).expect(&format!(
-                    "Jump map entry not defined for fill jump at {}",
-                    self.pointer
-                )))
-            }
+            FIL_JUMP => *self
+                .jump_map
+                .get(&self.pointer)
+                .ok_or(TrapType::UnmatchedLoop(self.pointer))?,
             FIL_CHAR => self.pointer + 1,
-            c => panic!(format!("Attempt to run undefined code: {}", c)),
+            c => return Err(TrapType::UndefinedOpcode(c)),
         };
-        Ok(true)
+        Ok(next)
     }
 
     /// Runs `BFVM::next` for the number of times given. If `cycles` is 0, runs until program
@@ -289,7 +570,7 @@ impl<'a> BFVM<'a> {
         } else {
             let mut left = cycles;
             while left > 0 && self.next()? {
-                left += 1;
+                left -= 1;
             }
         }
 
@@ -4,6 +4,9 @@ pub enum VMErrKind {
    OverlappingPeripheralAddresses(usize, u32),  // peripheral vector length, smallest native address
    InvalidPeripheralTapeAccess(u16),            // peripheral tape address
    UnmachedLoopParentheses(usize),              // unmached parenthesis location
+   PeripheralWindowOutOfRange(u16, u16),        // window base, window length
+   OverlappingPeripheralWindow(u16, u16, u16),  // page, base, length
+   MalformedBytecode(usize),                    // byte offset where decoding failed, or out-of-range fill-marker position
 }
 
 